@@ -16,7 +16,7 @@ use xorshift::Rng;
 fn bench_insert_and_calculate(c: &mut Criterion) {
 
     c.bench_function("insert_and_calculate", |b| {
-        let mut median_tracker = StreamingMedian::new(123);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123);
 
         b.iter(|| {
             median_tracker.insert_and_calculate(100);
@@ -31,7 +31,7 @@ fn bench_insert_and_calculate_rand(c: &mut Criterion) {
     c.bench_function("insert_and_calculate_rand", |b| {
         let mut rng = Xoroshiro128::from_seed(&[1, 71, 1223]);
 
-        let mut median_tracker = StreamingMedian::new(123_000);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123_000);
 
         b.iter(|| {
             median_tracker.insert_and_calculate(rng.gen());
@@ -45,7 +45,7 @@ fn bench_insert_and_calculate_rand_within_bound(c: &mut Criterion) {
     c.bench_function("insert_and_calculate_rand_within_bound", |b| {
         let mut rng = Xoroshiro128::from_seed(&[1, 71, 1223]);
 
-        let mut median_tracker = StreamingMedian::new(5);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(5);
 
         rng.gen_range(1, 10);
         b.iter(|| {
@@ -1,3 +1,9 @@
+//! A fixed-size stack buffer with no allocation, so the whole crate works
+//! in `no_std` contexts (embedded/DSP, sensor streams, etc). `std` is still
+//! the default, and enabled by the `std` feature; disable default features
+//! to build without it.
+#![cfg_attr(not(feature = "std"), no_std)]
+
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
@@ -6,26 +12,35 @@ extern crate xorshift;
 extern crate arraydeque;
 
 use arraydeque::ArrayDeque;
-use std::iter::{self, FromIterator};
-use std::cmp::Ordering;
-use std::mem::uninitialized;
+use core::iter::{self, FromIterator};
+use core::cmp::Ordering;
+
+mod p2;
+pub use p2::P2Quantile;
 
 
 /// `StreamingMedian` provides a simple interface for inserting values
-/// and calculating medians.
-pub struct StreamingMedian {
-    data: ArrayDeque<[u32; 64]>,
-    sorted: [u32; 64],
-    last_median: u32,
+/// and calculating medians over a sliding window of `N` elements.
+///
+/// `T` is the element type being tracked (e.g. `u32`, `i64`, or any other
+/// `Ord + Copy` type) and `N` is the compile-time window size. The window
+/// size used to be hard-coded to 64 `u32`s; it is now just the default
+/// choice callers make at the call site, e.g. `StreamingMedian::<u32, 64>`.
+pub struct StreamingMedian<T, const N: usize> {
+    data: ArrayDeque<T, N>,
+    sorted: [T; N],
+    last_median: T,
 }
 
-impl StreamingMedian {
-    pub fn new(initial_median: u32) -> StreamingMedian {
-        let data = ArrayDeque::from_iter(iter::repeat(initial_median).take(64));
+impl<T, const N: usize> StreamingMedian<T, N>
+    where T: Ord + Copy
+{
+    pub fn new(initial_median: T) -> StreamingMedian<T, N> {
+        let data = ArrayDeque::from_iter(iter::repeat(initial_median).take(N));
 
         // We use unsafe here and then immediately assign values to the
         // unused space
-        let mut sorted: [u32; 64] = [0; 64];
+        let mut sorted: [T; N] = [initial_median; N];
 
         for (i, t) in data.iter().enumerate() {
             sorted[i] = *t;
@@ -42,12 +57,12 @@ impl StreamingMedian {
     ///
     /// # Example
     /// ```norun
-    /// use sqs_service_handler::autoscaling::median;
+    /// use streaming_median::StreamingMedian;
     ///
-    /// let stream = StreamingMedian::new(123_000);
-    /// assert_eq!(stream.last(), 31_000);
+    /// let stream = StreamingMedian::<u32, 64>::new(123_000);
+    /// assert_eq!(stream.last(), 123_000);
     /// ```
-    pub fn last(&self) -> u32 {
+    pub fn last(&self) -> T {
         self.last_median
     }
 
@@ -58,10 +73,10 @@ impl StreamingMedian {
     /// * `value` - The value to be inserted into the stream
     /// # Example
     /// ```norun
-    /// use sqs_service_handler::autoscaling::median;
+    /// use streaming_median::StreamingMedian;
     ///
-    /// let stream = StreamingMedian::new(123_000);
-    /// assert_eq!(stream.insert_and_calculate(31_000), 31_000);
+    /// let mut stream = StreamingMedian::<u32, 64>::new(123_000);
+    /// assert_eq!(stream.insert_and_calculate(31_000), 123_000);
     /// ```
     /// The algorithm used to efficiently insert and calculate relies
     /// on the fact that the data is always left in a sorted state.
@@ -99,16 +114,95 @@ impl StreamingMedian {
     ///
     /// A similar approach is performed in the case of the insert_index being before
     /// the remove index.
+    pub fn insert_and_calculate(&mut self, value: T) -> T {
+        self.insert(value);
+
+        let median = self.sorted[median_index(N)];
+        self.last_median = median;
+        median
+    }
+
+    /// Calculates and returns the median as an `f64`, averaging the two
+    /// central elements when the window size `N` is even instead of taking
+    /// only the lower of the two (what [`insert_and_calculate`] and [`last`]
+    /// do, for a fast integer-only path).
+    ///
+    /// [`insert_and_calculate`]: Self::insert_and_calculate
+    /// [`last`]: Self::last
+    ///
+    /// # Example
+    /// ```norun
+    /// use streaming_median::StreamingMedian;
+    ///
+    /// let mut stream = StreamingMedian::<u32, 64>::new(123_000);
+    /// assert_eq!(stream.insert_and_calculate_median(31_000), 123_000.0);
+    /// ```
+    pub fn insert_and_calculate_median(&mut self, value: T) -> f64
+        where T: Mean
+    {
+        self.insert(value);
+
+        let median = if N % 2 == 0 {
+            self.sorted[N / 2 - 1].mean(self.sorted[N / 2])
+        } else {
+            let mid = self.sorted[median_index(N)];
+            mid.mean(mid)
+        };
+
+        self.last_median = self.sorted[median_index(N)];
+        median
+    }
+
+    /// Returns the value at quantile `q` (clamped to `[0.0, 1.0]`) within
+    /// the current window, without inserting anything.
+    ///
+    /// # Example
+    /// ```norun
+    /// use streaming_median::StreamingMedian;
     ///
-    /// Unsafe is used here to dramatically improve performance - a full 3-5x
-    pub fn insert_and_calculate(&mut self, value: u32) -> u32 {
-        let mut scratch_space: [u32; 64] = unsafe { uninitialized() };
+    /// let stream = StreamingMedian::<u32, 64>::new(123_000);
+    /// assert_eq!(stream.quantile(0.9), 123_000);
+    /// ```
+    pub fn quantile(&self, q: f64) -> T {
+        self.sorted[quantile_index(N, q)]
+    }
 
+    /// Inserts `value` and returns the value at quantile `q` (clamped to
+    /// `[0.0, 1.0]`) within the resulting window, e.g. `q = 0.9` for p90
+    /// latency over a sliding window of samples.
+    ///
+    /// Uses nearest-rank selection on the sorted window - the same
+    /// O(N)-shift insert paid for by [`insert_and_calculate`], generalized
+    /// from the fixed median index to an arbitrary rank.
+    ///
+    /// [`insert_and_calculate`]: Self::insert_and_calculate
+    ///
+    /// # Example
+    /// ```norun
+    /// use streaming_median::StreamingMedian;
+    ///
+    /// let mut stream = StreamingMedian::<u32, 64>::new(123_000);
+    /// assert_eq!(stream.insert_and_calculate_quantile(31_000, 0.9), 123_000);
+    /// ```
+    pub fn insert_and_calculate_quantile(&mut self, value: T, q: f64) -> T {
+        self.insert(value);
+        self.sorted[quantile_index(N, q)]
+    }
+
+    /// Pops the oldest value and inserts `value` in its place, shifting
+    /// `self.sorted` so that it stays sorted. Does not touch
+    /// `self.last_median`; callers read whichever median representation
+    /// they need out of `self.sorted` afterwards.
+    ///
+    /// The shift is a rotate-by-one of the subslice between `remove_index`
+    /// and `insert_index`, so it's done in place with `copy_within` - no
+    /// scratch buffer, no unsafe.
+    fn insert(&mut self, value: T) {
         let removed = self.data.pop_front().unwrap();
         let _ = self.data.push_back(value);  // If we pop_front, push_back can never fail
 
         if removed == value {
-            return self.sorted[31];
+            return;
         }
 
         let remove_index = binary_search(&self.sorted, &removed);
@@ -132,42 +226,73 @@ impl StreamingMedian {
         if remove_index < insert_index {
             // Starting with a self.sorted of
             // [2, 3, 4, 5, 7, 8]
-            // insert_and_calculate(6)
+            // insert(6)
             // [2, 3, 4, 5, 7, 8] <- remove_index = 1, insert_index = 3
             // [2, 4, 5, 5, 7, 8]
             // [2, 4, 5, 6, 7, 8]
 
-            scratch_space[remove_index + 1..insert_index]
-                .copy_from_slice(&self.sorted[remove_index + 1..insert_index]);
-
-            self.sorted[remove_index..insert_index - 1]
-                .copy_from_slice(&scratch_space[remove_index + 1..insert_index]);
-
+            self.sorted.copy_within(remove_index + 1..insert_index, remove_index);
             self.sorted[insert_index - 1] = value;
 
         } else {
             // Starting with a self.sorted of
             // [2, 3, 4, 5, 7, 8, 9]
-            // insert_and_calculate(6)
+            // insert(6)
             // [2, 3, 4, 5, 7, 8, 9] <- remove_index = 5, insert_index = 3
             // [2, 3, 4, 5, 5, 7, 9] Shift values
             // [2, 3, 4, 6, 7, 8, 9] Insert value
-            scratch_space[insert_index..remove_index]
-                .copy_from_slice(&self.sorted[insert_index..remove_index]);
-
-            self.sorted[insert_index + 1..remove_index + 1]
-                .copy_from_slice(&scratch_space[insert_index..remove_index]);
-
+            self.sorted.copy_within(insert_index..remove_index, insert_index + 1);
             self.sorted[insert_index] = value;
 
         }
-
-        let median = self.sorted[31];
-        self.last_median = median;
-        median
     }
 }
 
+/// Types whose pairwise average can be taken as an `f64`, used by
+/// [`StreamingMedian::insert_and_calculate_median`] to interpolate between
+/// the two central elements of an even-sized window.
+pub trait Mean: Copy {
+    fn mean(self, other: Self) -> f64;
+}
+
+macro_rules! impl_mean {
+    ($($t:ty),*) => {
+        $(
+            impl Mean for $t {
+                fn mean(self, other: Self) -> f64 {
+                    (self as f64 + other as f64) / 2.0
+                }
+            }
+        )*
+    };
+}
+
+impl_mean!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+/// Index of the (lower, for an even window) median within a sorted window
+/// of `n` elements. For `n == 64` this is `31`, matching the index the
+/// window size used to be hard-coded against.
+const fn median_index(n: usize) -> usize {
+    (n - 1) / 2
+}
+
+/// Nearest-rank index for quantile `q` (clamped to `[0.0, 1.0]`) within a
+/// sorted window of `n` elements, per `q * (n - 1)` rounded to the closest
+/// index.
+fn quantile_index(n: usize, q: f64) -> usize {
+    let q = if q < 0.0 {
+        0.0
+    } else if q > 1.0 {
+        1.0
+    } else {
+        q
+    };
+
+    // `f64::round` needs `std`; for a non-negative value adding 0.5 and
+    // truncating rounds to the closest index the same way.
+    (q * (n - 1) as f64 + 0.5) as usize
+}
+
 fn binary_search<T>(t: &[T], x: &T) -> usize where T: Ord {
     binary_search_by(t, |p| p.cmp(x))
 }
@@ -198,6 +323,60 @@ fn binary_search_by<T, F>(t: &[T], mut f: F) -> usize
     }
 }
 
+/// Iterator returned by [`median_filter`] and [`MedianFiltered::median_filtered`],
+/// yielding the running median of an underlying iterator.
+pub struct MedianFilter<I, T, const N: usize> {
+    iter: I,
+    window: StreamingMedian<T, N>,
+}
+
+impl<I, T, const N: usize> Iterator for MedianFilter<I, T, N>
+    where I: Iterator<Item = T>,
+          T: Ord + Copy
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.next().map(|x| self.window.insert_and_calculate(x))
+    }
+}
+
+/// Feeds `iter` through `window`'s [`insert_and_calculate`], yielding the
+/// running median after each sample - a one-pass median filter over a
+/// signal, suppressing transient spikes while preserving sustained steps.
+///
+/// `window` is already seeded (via [`StreamingMedian::new`]) with whatever
+/// value it was constructed with, so the first few yielded values are
+/// still partly the seed rather than purely derived from `iter`; callers
+/// who care can skip them.
+///
+/// [`insert_and_calculate`]: StreamingMedian::insert_and_calculate
+pub fn median_filter<I, T, const N: usize>(iter: I, window: StreamingMedian<T, N>) -> MedianFilter<I::IntoIter, T, N>
+    where I: IntoIterator<Item = T>,
+          T: Ord + Copy
+{
+    MedianFilter {
+        iter: iter.into_iter(),
+        window,
+    }
+}
+
+/// Adapts any iterator into a running median filter, so callers can do
+/// `samples.into_iter().median_filtered(window)` to denoise a slice or
+/// stream in one pass.
+pub trait MedianFiltered: IntoIterator + Sized {
+    fn median_filtered<const N: usize>(self, window: StreamingMedian<Self::Item, N>) -> MedianFilter<Self::IntoIter, Self::Item, N>
+        where Self::Item: Ord + Copy;
+}
+
+impl<I: IntoIterator> MedianFiltered for I {
+    fn median_filtered<const N: usize>(self, window: StreamingMedian<Self::Item, N>) -> MedianFilter<Self::IntoIter, Self::Item, N>
+        where Self::Item: Ord + Copy
+    {
+        median_filter(self, window)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -221,7 +400,7 @@ mod test {
         let t = millis(SystemTime::now().duration_since(UNIX_EPOCH).unwrap());
         let mut rng = Xoroshiro128::from_seed(&[t, 71, 1223]);
 
-        let mut median_tracker = StreamingMedian::new(123_000);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123_000);
         for _ in 0..100_000 {
             median_tracker.insert_and_calculate(rng.gen());
         }
@@ -233,7 +412,7 @@ mod test {
 
     #[test]
     fn test_median_ascending() {
-        let mut median_tracker = StreamingMedian::new(123_000);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123_000);
 
         let mut ascending_iter = 0..;
         for _ in 0..100_000 {
@@ -247,7 +426,7 @@ mod test {
 
     #[test]
     fn test_median_descending() {
-        let mut median_tracker = StreamingMedian::new(123_000);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123_000);
 
         let mut ascending_iter = 200_000..;
         for _ in 0..100_000 {
@@ -261,7 +440,7 @@ mod test {
 
     #[test]
     fn test_poison_absence() {
-        let mut median_tracker = StreamingMedian::new(123_000);
+        let mut median_tracker = StreamingMedian::<u32, 64>::new(123_000);
 
         for _ in 0..64 {
             median_tracker.insert_and_calculate(1);
@@ -272,9 +451,141 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_shift_remove_before_insert() {
+        // The oldest value (3) sits to the left of where the new value (6)
+        // belongs in sorted order, so remove_index < insert_index.
+        let mut median_tracker = StreamingMedian::<u32, 6> {
+            data: ArrayDeque::from_iter([3, 4, 5, 7, 8, 2]),
+            sorted: [2, 3, 4, 5, 7, 8],
+            last_median: 4,
+        };
+
+        median_tracker.insert_and_calculate(6);
+
+        let mut expected = vec![2, 4, 5, 7, 8, 6];
+        expected.sort();
+        assert_eq!(median_tracker.sorted.to_vec(), expected);
+
+        for i in median_tracker.sorted.windows(2) {
+            assert!(i[0] <= i[1]);
+        }
+    }
+
+    #[test]
+    fn test_shift_remove_after_insert() {
+        // The oldest value (8) sits to the right of where the new value (6)
+        // belongs in sorted order, so remove_index >= insert_index.
+        let mut median_tracker = StreamingMedian::<u32, 7> {
+            data: ArrayDeque::from_iter([8, 2, 3, 4, 5, 7, 9]),
+            sorted: [2, 3, 4, 5, 7, 8, 9],
+            last_median: 5,
+        };
+
+        median_tracker.insert_and_calculate(6);
+
+        let mut expected = vec![2, 3, 4, 5, 7, 9, 6];
+        expected.sort();
+        assert_eq!(median_tracker.sorted.to_vec(), expected);
+
+        for i in median_tracker.sorted.windows(2) {
+            assert!(i[0] <= i[1]);
+        }
+    }
+
+    #[test]
+    fn test_interpolated_median_even_window() {
+        // A window of [1, 1, 2, 2] has no single middle element, so the
+        // interpolated median should be the average of the two central
+        // values, 1 and 2.
+        let mut median_tracker = StreamingMedian::<u32, 4>::new(1);
+
+        median_tracker.insert_and_calculate(1);
+        median_tracker.insert_and_calculate(2);
+        let median = median_tracker.insert_and_calculate_median(2);
+
+        assert_eq!(median, 1.5);
+    }
+
+    #[test]
+    fn test_interpolated_median_odd_window() {
+        let mut median_tracker = StreamingMedian::<u32, 5>::new(1);
+
+        median_tracker.insert_and_calculate(1);
+        median_tracker.insert_and_calculate(2);
+        median_tracker.insert_and_calculate(2);
+        let median = median_tracker.insert_and_calculate_median(2);
+
+        assert_eq!(median, 2.0);
+    }
+
+    #[test]
+    fn test_quantile() {
+        let mut median_tracker = StreamingMedian::<u32, 11>::new(1);
+        for v in 2..=11 {
+            median_tracker.insert_and_calculate(v);
+        }
+
+        assert_eq!(median_tracker.quantile(0.0), 1);
+        assert_eq!(median_tracker.quantile(0.5), 6);
+        assert_eq!(median_tracker.quantile(0.9), 10);
+        assert_eq!(median_tracker.quantile(1.0), 11);
+    }
+
+    #[test]
+    fn test_insert_and_calculate_quantile() {
+        let mut median_tracker = StreamingMedian::<u32, 11>::new(1);
+        for v in 2..=10 {
+            median_tracker.insert_and_calculate(v);
+        }
+
+        let p90 = median_tracker.insert_and_calculate_quantile(11, 0.9);
+
+        assert_eq!(p90, 10);
+    }
+
+    #[test]
+    fn test_p2_median_converges() {
+        // A uniform integer stream has a well-known true median; P2Quantile
+        // should land close to it without ever storing the stream.
+        let mut rng = Xoroshiro128::from_seed(&[42, 71, 1223]);
+        let mut estimator = P2Quantile::new(0.5);
+
+        for _ in 0..10_000 {
+            let x: u32 = rng.gen_range(0, 1000);
+            estimator.add(x as f64);
+        }
+
+        assert!((estimator.median() - 500.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn test_median_filter_suppresses_spike() {
+        let window = StreamingMedian::<i32, 5>::new(0);
+        let input = vec![0, 0, 0, 0, 100, 0, 0, 0, 0, 0];
+
+        let output: Vec<i32> = median_filter(input, window).collect();
+
+        assert!(output.iter().all(|&x| x != 100));
+    }
+
+    #[test]
+    fn test_median_filter_preserves_step() {
+        let window = StreamingMedian::<i32, 5>::new(0);
+        let input = vec![0, 0, 0, 0, 0, 9, 9, 9, 9, 9, 9, 9, 9];
+
+        let output: Vec<i32> = input.median_filtered(window).collect();
+
+        // A sustained step, unlike a transient spike, should survive the
+        // filter: once enough 9s have entered the window of 5 to outnumber
+        // the 0s, the running median locks onto 9 and stays there.
+        assert_eq!(output[7], 9);
+        assert!(output[7..].iter().all(|&x| x == 9));
+    }
+
     quickcheck! {
         fn maintains_sorted(default: u32, input: u32) -> bool {
-            let mut median_tracker = StreamingMedian::new(default   );
+            let mut median_tracker = StreamingMedian::<u32, 64>::new(default);
             median_tracker.insert_and_calculate(input);
 
             for i in median_tracker.sorted.windows(2) {
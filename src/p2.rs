@@ -0,0 +1,165 @@
+//! The P² ("Piecewise-Parabolic") algorithm estimates a quantile over an
+//! *unbounded* stream in O(1) memory, as opposed to [`StreamingMedian`]
+//! which keeps the full sliding window around to answer the same question
+//! for the last `N` samples.
+//!
+//! [`StreamingMedian`]: crate::StreamingMedian
+//!
+//! Jain, R. and Chlamtac, I., "The P² Algorithm for Dynamic Calculation of
+//! Quantiles and Histograms Without Storing Observations", 1985.
+
+/// Number of markers the P² algorithm maintains: the minimum, the two
+/// markers either side of the target quantile, the target quantile itself,
+/// and the maximum.
+const MARKERS: usize = 5;
+
+/// Estimates a single quantile `p` over an unbounded stream using the P²
+/// algorithm: five markers track heights, actual positions, and desired
+/// positions, and are nudged towards their desired positions - by a
+/// parabolic fit, falling back to linear interpolation - as each new
+/// observation arrives.
+pub struct P2Quantile {
+    p: f64,
+    /// Buffer for the first `MARKERS` observations, used to seed the
+    /// markers; `None` once initialized.
+    warm_up: Option<[f64; MARKERS]>,
+    warm_up_len: usize,
+    /// Marker heights - the quantile estimates themselves.
+    q: [f64; MARKERS],
+    /// Actual marker positions.
+    n: [i64; MARKERS],
+    /// Desired (real-valued) marker positions.
+    np: [f64; MARKERS],
+    /// Per-observation increments to the desired marker positions.
+    dn: [f64; MARKERS],
+}
+
+impl P2Quantile {
+    /// Creates an estimator for quantile `p` (e.g. `0.5` for the median,
+    /// `0.9` for p90). `p` is clamped to `[0.0, 1.0]`.
+    pub fn new(p: f64) -> P2Quantile {
+        let p = if p < 0.0 {
+            0.0
+        } else if p > 1.0 {
+            1.0
+        } else {
+            p
+        };
+
+        P2Quantile {
+            p,
+            warm_up: Some([0.0; MARKERS]),
+            warm_up_len: 0,
+            q: [0.0; MARKERS],
+            n: [0, 0, 0, 0, 0],
+            np: [0.0; MARKERS],
+            dn: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    /// Feeds a new observation into the estimator.
+    pub fn add(&mut self, x: f64) {
+        if let Some(mut warm_up) = self.warm_up.take() {
+            warm_up[self.warm_up_len] = x;
+            self.warm_up_len += 1;
+
+            if self.warm_up_len < MARKERS {
+                self.warm_up = Some(warm_up);
+                return;
+            }
+
+            warm_up.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+            self.q = warm_up;
+            for i in 0..MARKERS {
+                self.n[i] = i as i64 + 1;
+            }
+            self.np = [
+                1.0,
+                1.0 + 2.0 * self.p,
+                1.0 + 4.0 * self.p,
+                3.0 + 2.0 * self.p,
+                5.0,
+            ];
+            return;
+        }
+
+        let k = if x < self.q[0] {
+            self.q[0] = x;
+            0
+        } else if x >= self.q[4] {
+            self.q[4] = x;
+            3
+        } else {
+            (0..4)
+                .find(|&i| self.q[i] <= x && x < self.q[i + 1])
+                .unwrap()
+        };
+
+        for n in self.n.iter_mut().skip(k + 1) {
+            *n += 1;
+        }
+
+        for i in 0..MARKERS {
+            self.np[i] += self.dn[i];
+        }
+
+        for i in 1..4 {
+            self.adjust_marker(i);
+        }
+    }
+
+    /// Nudges marker `i` (one of the three interior markers) one position
+    /// towards its desired position `np[i]`, via the parabolic formula,
+    /// falling back to linear interpolation if the parabolic estimate
+    /// would break the `q[i - 1] < q[i] < q[i + 1]` invariant.
+    fn adjust_marker(&mut self, i: usize) {
+        let d = self.np[i] - self.n[i] as f64;
+
+        let move_right = d >= 1.0 && self.n[i + 1] - self.n[i] > 1;
+        let move_left = d <= -1.0 && self.n[i - 1] - self.n[i] < -1;
+
+        if !move_right && !move_left {
+            return;
+        }
+
+        let s: i64 = if d >= 0.0 { 1 } else { -1 };
+        let s_f = s as f64;
+
+        let parabolic = self.q[i]
+            + s_f / (self.n[i + 1] - self.n[i - 1]) as f64
+                * ((self.n[i] - self.n[i - 1] + s) as f64 * (self.q[i + 1] - self.q[i])
+                    / (self.n[i + 1] - self.n[i]) as f64
+                    + (self.n[i + 1] - self.n[i] - s) as f64 * (self.q[i] - self.q[i - 1])
+                        / (self.n[i] - self.n[i - 1]) as f64);
+
+        self.q[i] = if self.q[i - 1] < parabolic && parabolic < self.q[i + 1] {
+            parabolic
+        } else {
+            let (neighbor_q, neighbor_n) = if s == 1 {
+                (self.q[i + 1], self.n[i + 1])
+            } else {
+                (self.q[i - 1], self.n[i - 1])
+            };
+
+            self.q[i] + s_f * (neighbor_q - self.q[i]) / (neighbor_n - self.n[i]) as f64
+        };
+
+        self.n[i] += s;
+    }
+
+    /// Returns the current estimate of the configured quantile. Before the
+    /// first `MARKERS` observations have been seen this is `0.0`, since
+    /// there isn't yet enough data to seed the markers.
+    pub fn quantile(&self) -> f64 {
+        self.q[2]
+    }
+
+    /// Convenience accessor for the common case of tracking the median
+    /// (`p = 0.5`). Returns the same value as [`quantile`] regardless of
+    /// which `p` this estimator was actually constructed with.
+    ///
+    /// [`quantile`]: Self::quantile
+    pub fn median(&self) -> f64 {
+        self.quantile()
+    }
+}